@@ -1,69 +1,317 @@
 use memmap2::Mmap;
 use pyo3::prelude::*;
 use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
 
-#[pyclass]
-struct IpSearcher {
-    mmap: Mmap,
+/// 某一版本 `.bin` 文件映射好之后的全部状态：mmap 本身 + 各区段的起始偏移。
+/// `reload` 整体替换这个结构体，查询方把它 clone 出来（`Arc` 只是加一次引用计数，
+/// 其余都是廉价的 usize/bool 拷贝），离开读锁之后再用快照里的裸指针做查找，
+/// 旧文件在最后一个持有者释放前都不会被 unmap。
+#[derive(Clone)]
+struct Inner {
+    mmap: Arc<Mmap>,
     nodes_start: usize,
     values_start: usize,
     values_count: usize,
+    // PTV3 专属：字符串 offset/length 表起始位置 + UTF-8 数据块起始位置。
+    // PTV2 文件没有这个区块，has_strings 为 false 时两者都是 0。
+    strings_table_start: usize,
+    strings_blob_start: usize,
+    has_strings: bool,
+    // 可选的 256x256 前两字节加速表起始位置，由 header 的 flags 字节标记是否存在。
+    front_table_start: usize,
+    has_front_table: bool,
 }
 
-#[pymethods]
-impl IpSearcher {
-    #[new]
-    fn new(path: String) -> PyResult<Self> {
+impl Inner {
+    fn load(path: &str) -> PyResult<Inner> {
         let file = File::open(path)?;
         let mmap = unsafe { Mmap::map(&file)? };
         let mut nodes_start = 0;
         let mut values_start = mmap.len();
         let mut values_count = 0;
+        let mut strings_table_start = 0;
+        let mut strings_blob_start = 0;
+        let mut has_strings = false;
+        let mut front_table_start = 0;
+        let mut has_front_table = false;
+
+        let is_v2 = mmap.len() >= IpSearcher::HEADER_SIZE && &mmap[0..4] == IpSearcher::MAGIC;
+        let is_v3 = mmap.len() >= IpSearcher::HEADER_SIZE && &mmap[0..4] == IpSearcher::MAGIC_V3;
 
-        if mmap.len() >= Self::HEADER_SIZE && &mmap[0..4] == Self::MAGIC {
+        if is_v2 || is_v3 {
             let node_count = u32::from_le_bytes(mmap[4..8].try_into().unwrap()) as usize;
             values_count = u32::from_le_bytes(mmap[8..12].try_into().unwrap()) as usize;
-            let nodes_bytes = node_count.checked_mul(Self::NODE_SIZE).ok_or_else(|| {
-                PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                    "Invalid bin file: node count overflow.",
-                )
-            })?;
+            let flags = mmap[12];
+            has_front_table = flags & IpSearcher::FLAG_FRONT_TABLE != 0;
+
+            let nodes_bytes = node_count
+                .checked_mul(IpSearcher::NODE_SIZE)
+                .ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                        "Invalid bin file: node count overflow.",
+                    )
+                })?;
             let values_bytes = values_count.checked_mul(2).ok_or_else(|| {
                 PyErr::new::<pyo3::exceptions::PyValueError, _>(
                     "Invalid bin file: values count overflow.",
                 )
             })?;
-            let expected_len = Self::HEADER_SIZE + nodes_bytes + values_bytes;
-            if mmap.len() != expected_len {
+            nodes_start = IpSearcher::HEADER_SIZE;
+            values_start = IpSearcher::HEADER_SIZE + nodes_bytes;
+
+            // 各区段固定顺序：header -> nodes -> values -> [front table] -> [string pool]
+            let mut cursor = values_start + values_bytes;
+
+            if has_front_table {
+                let front_table_bytes = IpSearcher::FRONT_TABLE_ENTRIES * 4;
+                front_table_start = cursor;
+                if mmap.len() < front_table_start + front_table_bytes {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                        "Invalid bin file: front table truncated.",
+                    ));
+                }
+                cursor = front_table_start + front_table_bytes;
+            }
+
+            if is_v3 {
+                // 第四段：values_count 个 (u32 offset, u32 len) 条目，随后是 UTF-8 数据块。
+                // 数据块长度不固定，这里只校验表本身放得下，不再要求文件长度精确匹配。
+                let strings_table_bytes = values_count.checked_mul(8).ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                        "Invalid bin file: string table count overflow.",
+                    )
+                })?;
+                strings_table_start = cursor;
+                strings_blob_start = strings_table_start + strings_table_bytes;
+                if mmap.len() < strings_blob_start {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                        "Invalid bin file: string pool truncated.",
+                    ));
+                }
+                has_strings = true;
+            } else if mmap.len() != cursor {
                 return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
                     "Invalid bin file: size mismatch.",
                 ));
             }
-            nodes_start = Self::HEADER_SIZE;
-            values_start = Self::HEADER_SIZE + nodes_bytes;
-        } else if mmap.len() % Self::NODE_SIZE != 0 {
+        } else if mmap.len() % IpSearcher::NODE_SIZE != 0 {
             return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
                 "Invalid bin file: alignment mismatch (expected 72).",
             ));
         }
 
-        Ok(IpSearcher {
-            mmap,
+        Ok(Inner {
+            mmap: Arc::new(mmap),
             nodes_start,
             values_start,
             values_count,
+            strings_table_start,
+            strings_blob_start,
+            has_strings,
+            front_table_start,
+            has_front_table,
+        })
+    }
+
+    /// 根据 value_index 从字符串池里切出字符串，越界或非法 UTF-8 时返回 None。
+    /// 返回拥有所有权的 `String`（而非 `&str`）：快照只在查询方法里短暂存活，
+    /// 不能把引用带出函数。
+    fn resolve_string(&self, value_index: usize) -> Option<String> {
+        if value_index >= self.values_count {
+            return None;
+        }
+
+        unsafe {
+            let entry_ptr = self
+                .mmap
+                .as_ptr()
+                .add(self.strings_table_start + value_index * 8);
+            let offset = (entry_ptr as *const u32).read_unaligned() as usize;
+            let len = (entry_ptr.add(4) as *const u32).read_unaligned() as usize;
+
+            let start = self.strings_blob_start + offset;
+            let end = start.checked_add(len)?;
+            if end > self.mmap.len() {
+                return None;
+            }
+
+            std::str::from_utf8(self.mmap.get_unchecked(start..end))
+                .ok()
+                .map(str::to_owned)
+        }
+    }
+}
+
+/// 用 `RwLock<Inner>` 包一层，使得 `reload` 可以原子地整体替换 mmap/各偏移量，
+/// 而不需要重新构造 `IpSearcher` 本身——服务进程可以在其他线程仍在查询时安全换库。
+#[pyclass]
+struct IpSearcher {
+    inner: RwLock<Inner>,
+}
+
+#[pymethods]
+impl IpSearcher {
+    #[new]
+    fn new(path: String) -> PyResult<Self> {
+        Ok(IpSearcher {
+            inner: RwLock::new(Inner::load(&path)?),
         })
     }
 
+    /// 原子热重载：先在锁外打开并校验新文件，再短暂持有写锁整体替换。
+    /// 已经进入查询、持有旧快照的调用不受影响，会自然用旧数据跑完。
+    fn reload(&self, path: String) -> PyResult<()> {
+        let new_inner = Inner::load(&path)?;
+        let mut guard = self.inner.write().unwrap();
+        *guard = new_inner;
+        Ok(())
+    }
+
     /// 核心查询逻辑：支持 IPv4 (4字节) 和 IPv6 (16字节)
     fn contains_ip(&self, ip_bytes: &[u8]) -> bool {
-        let mut cursor = self.nodes_start;
+        let snap = self.snapshot();
+        Self::contains_ip_with(&snap, ip_bytes)
+    }
+
+    /// `contains_ip` 的最长前缀匹配 (LPM) 版本；语义说明见 `contains_ip_lpm_with`。
+    fn contains_ip_lpm(&self, ip_bytes: &[u8]) -> bool {
+        let snap = self.snapshot();
+        Self::contains_ip_lpm_with(&snap, ip_bytes)
+    }
+
+    /// 返回国家代码 (u16)，未命中返回 0
+    fn lookup_code(&self, ip_bytes: &[u8]) -> u16 {
+        let snap = self.snapshot();
+        Self::lookup_code_with(&snap, ip_bytes)
+    }
+
+    /// `lookup_code` 的最长前缀匹配 (LPM) 版本；语义说明见 `lookup_code_lpm_with`。
+    fn lookup_code_lpm(&self, ip_bytes: &[u8]) -> u16 {
+        let snap = self.snapshot();
+        Self::lookup_code_lpm_with(&snap, ip_bytes)
+    }
+
+    fn contains_packed(&self, packed_ips: &[u8], is_v6: bool) -> Vec<bool> {
+        let snap = self.snapshot();
+        let ip_stride = if is_v6 { 16 } else { 4 };
+
+        // 使用 Rayon 并行处理；整批共用同一份快照，不会因为中途 reload 而撞见半新半旧的数据。
+        packed_ips
+            .par_chunks(ip_stride)
+            .map(|ip_chunk| Self::contains_ip_with(&snap, ip_chunk))
+            .collect()
+    }
+
+    fn contains_strings(&self, py: Python<'_>, ips: Vec<String>) -> Vec<bool> {
+        let snap = self.snapshot();
+        py.detach(|| {
+            ips.into_par_iter()
+                .map(|ip_str| match ip_str.parse::<IpAddr>() {
+                    Ok(IpAddr::V4(v4)) => Self::contains_ip_with(&snap, &v4.octets()),
+                    Ok(IpAddr::V6(v6)) => Self::contains_ip_with(&snap, &v6.octets()),
+                    Err(_) => false,
+                })
+                .collect()
+        })
+    }
+
+    fn lookup_codes_packed(&self, packed_ips: &[u8], is_v6: bool) -> Vec<u16> {
+        let snap = self.snapshot();
+        let ip_stride = if is_v6 { 16 } else { 4 };
+
+        // 使用 Rayon 并行处理
+        packed_ips
+            .par_chunks(ip_stride)
+            .map(|ip_chunk| Self::lookup_code_with(&snap, ip_chunk))
+            .collect()
+    }
+
+    fn lookup_codes_strings(&self, py: Python<'_>, ips: Vec<String>) -> Vec<u16> {
+        let snap = self.snapshot();
+        py.detach(|| {
+            ips.into_par_iter()
+                .map(|ip_str| match ip_str.parse::<IpAddr>() {
+                    Ok(IpAddr::V4(v4)) => Self::lookup_code_with(&snap, &v4.octets()),
+                    Ok(IpAddr::V6(v6)) => Self::lookup_code_with(&snap, &v6.octets()),
+                    Err(_) => 0,
+                })
+                .collect()
+        })
+    }
+
+    /// 返回字符串元数据（如 "country|province|city|isp"），仅 PTV3 文件有效；
+    /// 详见 `lookup_info_with`。
+    fn lookup_info(&self, ip_bytes: &[u8]) -> Option<String> {
+        let snap = self.snapshot();
+        Self::lookup_info_with(&snap, ip_bytes)
+    }
+
+    fn lookup_infos_packed(&self, packed_ips: &[u8], is_v6: bool) -> Vec<Option<String>> {
+        let snap = self.snapshot();
+        let ip_stride = if is_v6 { 16 } else { 4 };
+
+        // 使用 Rayon 并行处理
+        packed_ips
+            .par_chunks(ip_stride)
+            .map(|ip_chunk| Self::lookup_info_with(&snap, ip_chunk))
+            .collect()
+    }
+
+    fn lookup_infos_strings(&self, py: Python<'_>, ips: Vec<String>) -> Vec<Option<String>> {
+        let snap = self.snapshot();
+        py.detach(|| {
+            ips.into_par_iter()
+                .map(|ip_str| match ip_str.parse::<IpAddr>() {
+                    Ok(IpAddr::V4(v4)) => Self::lookup_info_with(&snap, &v4.octets()),
+                    Ok(IpAddr::V6(v6)) => Self::lookup_info_with(&snap, &v6.octets()),
+                    Err(_) => None,
+                })
+                .collect()
+        })
+    }
+}
+
+impl IpSearcher {
+    const NODE_SIZE: usize = 72;
+    const HEADER_SIZE: usize = 16;
+    const MAGIC: &'static [u8; 4] = b"PTV2";
+    const MAGIC_V3: &'static [u8; 4] = b"PTV3";
+    // header 第 12 字节（reserved 区的第一个字节）用作 flags。
+    const FLAG_FRONT_TABLE: u8 = 0x01;
+    const FRONT_TABLE_ENTRIES: usize = 65536;
+
+    /// 读锁只持有到 clone 完成为止：克隆一次 `Arc<Mmap>`（仅增加引用计数）和几个
+    /// 廉价的 usize/bool 字段，随后在快照上做 unsafe 指针运算，不再触碰锁。
+    fn snapshot(&self) -> Inner {
+        self.inner.read().unwrap().clone()
+    }
+
+    fn contains_ip_with(snap: &Inner, ip_bytes: &[u8]) -> bool {
+        let mut cursor = snap.nodes_start;
         // 获取裸指针以绕过切片边界检查
-        let base_ptr = self.mmap.as_ptr();
+        let base_ptr = snap.mmap.as_ptr();
+
+        // 前两字节加速表：直接跳到消费完 ip_bytes[0..2] 之后应处于的节点，
+        // 省掉最前面两轮 bitmap/popcount 计算。
+        let mut remaining = ip_bytes;
+        if snap.has_front_table && ip_bytes.len() >= 2 {
+            let idx = ((ip_bytes[0] as usize) << 8) | ip_bytes[1] as usize;
+            unsafe {
+                let entry_ptr = base_ptr.add(snap.front_table_start + idx * 4) as *const u32;
+                let entry = entry_ptr.read_unaligned();
+                if entry == u32::MAX {
+                    return false;
+                }
+                cursor = entry as usize;
+            }
+            remaining = &ip_bytes[2..];
+        }
 
-        for &byte in ip_bytes {
+        for &byte in remaining {
             unsafe {
                 let node_ptr = base_ptr.add(cursor);
 
@@ -94,7 +342,7 @@ impl IpSearcher {
                 let base_offset = (node_ptr.add(64) as *const u32).read_unaligned() as usize;
 
                 // 计算 ChildBitmap 中当前位之前的 1 的个数 (Popcount)
-                let count = self.popcount_unsafe(node_ptr, byte_index, bit_index as usize);
+                let count = Self::popcount_unsafe(node_ptr, byte_index, bit_index as usize);
 
                 cursor = base_offset + (count * Self::NODE_SIZE);
             }
@@ -102,12 +350,78 @@ impl IpSearcher {
         false
     }
 
+    /// 最长前缀匹配 (LPM) 版本：命中 LeafBitmap 时先记录下来，不立即返回，
+    /// 只有在 ChildBitmap 也不再有更深的路径时才返回最近一次记录的结果。
+    /// 用于同时存在短前缀和长前缀时（例如 /8 和 /24 都命中），取更精确的那个。
+    fn contains_ip_lpm_with(snap: &Inner, ip_bytes: &[u8]) -> bool {
+        let mut cursor = snap.nodes_start;
+        let base_ptr = snap.mmap.as_ptr();
+        let mut matched = false;
+
+        // 前两字节加速表：validate_front_table 要求数据集里不存在短于 /17 的前缀，
+        // 所以被跳过的前两层节点不可能带有 LeafBitmap 位，matched 在这里维持 false
+        // 也是安全的。
+        let mut remaining = ip_bytes;
+        if snap.has_front_table && ip_bytes.len() >= 2 {
+            let idx = ((ip_bytes[0] as usize) << 8) | ip_bytes[1] as usize;
+            unsafe {
+                let entry_ptr = base_ptr.add(snap.front_table_start + idx * 4) as *const u32;
+                let entry = entry_ptr.read_unaligned();
+                if entry == u32::MAX {
+                    return matched;
+                }
+                cursor = entry as usize;
+            }
+            remaining = &ip_bytes[2..];
+        }
+
+        for &byte in remaining {
+            unsafe {
+                let node_ptr = base_ptr.add(cursor);
+
+                let byte_index = (byte as usize) >> 3;
+                let bit_index = 7 - (byte & 7);
+                let bit_mask = 1 << bit_index;
+
+                let leaf_byte = *node_ptr.add(32 + byte_index);
+                if (leaf_byte & bit_mask) != 0 {
+                    matched = true;
+                }
+
+                let child_byte = *node_ptr.add(byte_index);
+                if (child_byte & bit_mask) == 0 {
+                    return matched;
+                }
+
+                let base_offset = (node_ptr.add(64) as *const u32).read_unaligned() as usize;
+                let count = Self::popcount_unsafe(node_ptr, byte_index, bit_index as usize);
+
+                cursor = base_offset + (count * Self::NODE_SIZE);
+            }
+        }
+        matched
+    }
+
     /// 返回国家代码 (u16)，未命中返回 0
-    fn lookup_code(&self, ip_bytes: &[u8]) -> u16 {
-        let mut cursor = self.nodes_start;
-        let base_ptr = self.mmap.as_ptr();
+    fn lookup_code_with(snap: &Inner, ip_bytes: &[u8]) -> u16 {
+        let mut cursor = snap.nodes_start;
+        let base_ptr = snap.mmap.as_ptr();
 
-        for &byte in ip_bytes {
+        let mut remaining = ip_bytes;
+        if snap.has_front_table && ip_bytes.len() >= 2 {
+            let idx = ((ip_bytes[0] as usize) << 8) | ip_bytes[1] as usize;
+            unsafe {
+                let entry_ptr = base_ptr.add(snap.front_table_start + idx * 4) as *const u32;
+                let entry = entry_ptr.read_unaligned();
+                if entry == u32::MAX {
+                    return 0;
+                }
+                cursor = entry as usize;
+            }
+            remaining = &ip_bytes[2..];
+        }
+
+        for &byte in remaining {
             unsafe {
                 let node_ptr = base_ptr.add(cursor);
 
@@ -118,7 +432,7 @@ impl IpSearcher {
                 // 1. Check Leaf
                 let leaf_byte = *node_ptr.add(32 + byte_index);
                 if (leaf_byte & bit_mask) != 0 {
-                    if self.values_count == 0 {
+                    if snap.values_count == 0 {
                         return 0;
                     }
 
@@ -126,15 +440,15 @@ impl IpSearcher {
 
                     // 计算 LeafBitmap popcount
                     let offset =
-                        self.popcount_unsafe(node_ptr.add(32), byte_index, bit_index as usize);
+                        Self::popcount_unsafe(node_ptr.add(32), byte_index, bit_index as usize);
 
                     let value_index = base_index + offset;
-                    if value_index >= self.values_count {
+                    if value_index >= snap.values_count {
                         return 0;
                     }
 
                     // 读取值: values_start + index * 2
-                    let value_pos = self.values_start + (value_index * 2);
+                    let value_pos = snap.values_start + (value_index * 2);
                     // 确保不越界 (虽然理论上逻辑保证了，但 values_start 计算依赖文件长度)
                     // 这里为了极致性能假设文件格式正确，使用 unsafe 读取
                     // 也可以用 get_unchecked
@@ -150,7 +464,7 @@ impl IpSearcher {
 
                 // 3. Jump
                 let base_offset = (node_ptr.add(64) as *const u32).read_unaligned() as usize;
-                let count = self.popcount_unsafe(node_ptr, byte_index, bit_index as usize);
+                let count = Self::popcount_unsafe(node_ptr, byte_index, bit_index as usize);
 
                 cursor = base_offset + (count * Self::NODE_SIZE);
             }
@@ -158,64 +472,130 @@ impl IpSearcher {
         0
     }
 
-    fn contains_packed(&self, packed_ips: &[u8], is_v6: bool) -> Vec<bool> {
-        let ip_stride = if is_v6 { 16 } else { 4 };
+    /// 最长前缀匹配 (LPM) 版本的 `lookup_code`：命中 LeafBitmap 时记录候选值但
+    /// 继续下探，直到 ChildBitmap 再无深入路径时才返回最近一次记录的值；
+    /// 全程未命中则返回 0。
+    fn lookup_code_lpm_with(snap: &Inner, ip_bytes: &[u8]) -> u16 {
+        let mut cursor = snap.nodes_start;
+        let base_ptr = snap.mmap.as_ptr();
+        let mut matched: u16 = 0;
 
-        // 使用 Rayon 并行处理
-        packed_ips
-            .par_chunks(ip_stride)
-            .map(|ip_chunk| self.contains_ip(ip_chunk))
-            .collect()
-    }
+        // 同 contains_ip_lpm_with：被加速表跳过的前两层节点不可能带有 LeafBitmap 位。
+        let mut remaining = ip_bytes;
+        if snap.has_front_table && ip_bytes.len() >= 2 {
+            let idx = ((ip_bytes[0] as usize) << 8) | ip_bytes[1] as usize;
+            unsafe {
+                let entry_ptr = base_ptr.add(snap.front_table_start + idx * 4) as *const u32;
+                let entry = entry_ptr.read_unaligned();
+                if entry == u32::MAX {
+                    return matched;
+                }
+                cursor = entry as usize;
+            }
+            remaining = &ip_bytes[2..];
+        }
 
-    fn contains_strings(&self, py: Python<'_>, ips: Vec<String>) -> Vec<bool> {
-        py.detach(|| {
-            ips.into_par_iter()
-                .map(|ip_str| match ip_str.parse::<IpAddr>() {
-                    Ok(IpAddr::V4(v4)) => self.contains_ip(&v4.octets()),
-                    Ok(IpAddr::V6(v6)) => self.contains_ip(&v6.octets()),
-                    Err(_) => false,
-                })
-                .collect()
-        })
-    }
+        for &byte in remaining {
+            unsafe {
+                let node_ptr = base_ptr.add(cursor);
 
-    fn lookup_codes_packed(&self, packed_ips: &[u8], is_v6: bool) -> Vec<u16> {
-        let ip_stride = if is_v6 { 16 } else { 4 };
+                let byte_index = (byte as usize) >> 3;
+                let bit_index = 7 - (byte & 7);
+                let bit_mask = 1 << bit_index;
 
-        // 使用 Rayon 并行处理
-        packed_ips
-            .par_chunks(ip_stride)
-            .map(|ip_chunk| self.lookup_code(ip_chunk))
-            .collect()
-    }
+                let leaf_byte = *node_ptr.add(32 + byte_index);
+                if (leaf_byte & bit_mask) != 0 && snap.values_count != 0 {
+                    let base_index = (node_ptr.add(68) as *const u32).read_unaligned() as usize;
+                    let offset =
+                        Self::popcount_unsafe(node_ptr.add(32), byte_index, bit_index as usize);
+                    let value_index = base_index + offset;
 
-    fn lookup_codes_strings(&self, py: Python<'_>, ips: Vec<String>) -> Vec<u16> {
-        py.detach(|| {
-            ips.into_par_iter()
-                .map(|ip_str| match ip_str.parse::<IpAddr>() {
-                    Ok(IpAddr::V4(v4)) => self.lookup_code(&v4.octets()),
-                    Ok(IpAddr::V6(v6)) => self.lookup_code(&v6.octets()),
-                    Err(_) => 0,
-                })
-                .collect()
-        })
+                    if value_index < snap.values_count {
+                        let value_pos = snap.values_start + (value_index * 2);
+                        let val_ptr = base_ptr.add(value_pos) as *const u16;
+                        matched = val_ptr.read_unaligned();
+                    }
+                }
+
+                let child_byte = *node_ptr.add(byte_index);
+                if (child_byte & bit_mask) == 0 {
+                    return matched;
+                }
+
+                let base_offset = (node_ptr.add(64) as *const u32).read_unaligned() as usize;
+                let count = Self::popcount_unsafe(node_ptr, byte_index, bit_index as usize);
+
+                cursor = base_offset + (count * Self::NODE_SIZE);
+            }
+        }
+        matched
     }
-}
 
-impl IpSearcher {
-    const NODE_SIZE: usize = 72;
-    const HEADER_SIZE: usize = 16;
-    const MAGIC: &'static [u8; 4] = b"PTV2";
+    /// 返回字符串元数据（如 "country|province|city|isp"），仅 PTV3 文件有效。
+    /// 与 `lookup_code` 共用同一套 LeafBitmap popcount 算法定位 value_index，
+    /// 再从字符串池里切出对应的字符串。快照只在本次调用里存活，因此这里返回
+    /// 拥有所有权的 `String` 而不是零拷贝的 `&str`。
+    fn lookup_info_with(snap: &Inner, ip_bytes: &[u8]) -> Option<String> {
+        if !snap.has_strings {
+            return None;
+        }
+
+        let mut cursor = snap.nodes_start;
+        let base_ptr = snap.mmap.as_ptr();
+
+        let mut remaining = ip_bytes;
+        if snap.has_front_table && ip_bytes.len() >= 2 {
+            let idx = ((ip_bytes[0] as usize) << 8) | ip_bytes[1] as usize;
+            unsafe {
+                let entry_ptr = base_ptr.add(snap.front_table_start + idx * 4) as *const u32;
+                let entry = entry_ptr.read_unaligned();
+                if entry == u32::MAX {
+                    return None;
+                }
+                cursor = entry as usize;
+            }
+            remaining = &ip_bytes[2..];
+        }
+
+        for &byte in remaining {
+            unsafe {
+                let node_ptr = base_ptr.add(cursor);
+
+                let byte_index = (byte as usize) >> 3;
+                let bit_index = 7 - (byte & 7);
+                let bit_mask = 1 << bit_index;
+
+                let leaf_byte = *node_ptr.add(32 + byte_index);
+                if (leaf_byte & bit_mask) != 0 {
+                    if snap.values_count == 0 {
+                        return None;
+                    }
+
+                    let base_index = (node_ptr.add(68) as *const u32).read_unaligned() as usize;
+                    let offset =
+                        Self::popcount_unsafe(node_ptr.add(32), byte_index, bit_index as usize);
+                    let value_index = base_index + offset;
+
+                    return snap.resolve_string(value_index);
+                }
+
+                let child_byte = *node_ptr.add(byte_index);
+                if (child_byte & bit_mask) == 0 {
+                    return None;
+                }
+
+                let base_offset = (node_ptr.add(64) as *const u32).read_unaligned() as usize;
+                let count = Self::popcount_unsafe(node_ptr, byte_index, bit_index as usize);
+
+                cursor = base_offset + (count * Self::NODE_SIZE);
+            }
+        }
+        None
+    }
 
     /// 内部使用的 unsafe popcount，假设 bitmap_ptr 有效
     #[inline(always)]
-    unsafe fn popcount_unsafe(
-        &self,
-        bitmap_ptr: *const u8,
-        byte_index: usize,
-        bit_index: usize,
-    ) -> usize {
+    unsafe fn popcount_unsafe(bitmap_ptr: *const u8, byte_index: usize, bit_index: usize) -> usize {
         let mut count: usize = 0;
 
         // 1. 以 u64 为单位统计，减少循环次数
@@ -262,8 +642,501 @@ impl IpSearcher {
     }
 }
 
+/// 构建期使用的树形节点，最终会被压平成 72 字节的定长节点数组。
+/// 叶子同时携带 u16 code 和可选的字符串元数据，两者共用同一个 value_index。
+#[derive(Default, Clone)]
+struct NodeBuilder {
+    children: BTreeMap<u8, NodeBuilder>,
+    leaves: BTreeMap<u8, (u16, Option<String>)>,
+}
+
+impl NodeBuilder {
+    /// 将一个 CIDR 前缀写入树中。`bytes`/`prefix_len` 已经过解析和校验。
+    fn insert(&mut self, bytes: &[u8], prefix_len: usize, code: u16, info: Option<String>) {
+        let full_bytes = prefix_len / 8;
+        let rem = prefix_len % 8;
+
+        if rem == 0 {
+            if full_bytes == 0 {
+                // /0：匹配所有地址，直接在根节点铺满 LeafBitmap。
+                for b in 0..=255u8 {
+                    self.leaves.insert(b, (code, info.clone()));
+                }
+                return;
+            }
+            let mut node = self;
+            for &b in &bytes[..full_bytes - 1] {
+                node = node.children.entry(b).or_default();
+            }
+            node.leaves.insert(bytes[full_bytes - 1], (code, info));
+        } else {
+            let mut node = self;
+            for &b in &bytes[..full_bytes] {
+                node = node.children.entry(b).or_default();
+            }
+
+            // 最后一个字节只固定了高 rem 位，剩下的 (8 - rem) 位展开成一段连续的叶子区间。
+            let shift = 8 - rem;
+            let base = bytes[full_bytes] & (!0u8 << shift);
+            let span = 1usize << shift;
+            for offset in 0..span {
+                node.leaves
+                    .insert(base + offset as u8, (code, info.clone()));
+            }
+        }
+    }
+}
+
+/// 将字节值写入 ChildBitmap/LeafBitmap，位序与 `IpSearcher` 的查找逻辑严格对应：
+/// bit 7（0x80）对应字节值 0，逐位右移对应字节值递增。
+fn set_bitmap_bit(bitmap: &mut [u8; 32], value: u8) {
+    let byte_index = (value as usize) >> 3;
+    let bit_index = 7 - (value & 7);
+    bitmap[byte_index] |= 1 << bit_index;
+}
+
+/// 与 `set_bitmap_bit` 同位序：bitmap 里是否记录了 `value` 这个字节值。
+fn bitmap_has(bitmap: &[u8; 32], value: u8) -> bool {
+    let byte_index = (value as usize) >> 3;
+    let bit_index = 7 - (value & 7);
+    (bitmap[byte_index] & (1 << bit_index)) != 0
+}
+
+/// 构建期用的安全版 popcount：统计 bitmap 中字节值严格小于 `value` 的已置位数量，
+/// 与运行时 `IpSearcher::popcount_unsafe` 的语义一致。
+fn popcount_before(bitmap: &[u8; 32], value: u8) -> usize {
+    let byte_index = (value as usize) >> 3;
+    let bit_index = 7 - (value & 7);
+
+    let mut count = 0usize;
+    for &b in &bitmap[..byte_index] {
+        count += b.count_ones() as usize;
+    }
+    let mask: u8 = if bit_index == 7 {
+        0
+    } else {
+        0xFFu8 << (bit_index + 1)
+    };
+    count += (bitmap[byte_index] & mask).count_ones() as usize;
+    count
+}
+
+fn parse_cidr(cidr: &str) -> PyResult<(Vec<u8>, usize)> {
+    let mut parts = cidr.splitn(2, '/');
+    let addr_str = parts.next().unwrap_or_default();
+    let len_str = parts.next().ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Invalid CIDR (missing prefix length): {cidr}"
+        ))
+    })?;
+
+    let addr: IpAddr = addr_str.parse().map_err(|_| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid IP address: {cidr}"))
+    })?;
+    let prefix_len: usize = len_str.parse().map_err(|_| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid prefix length: {cidr}"))
+    })?;
+
+    let bytes = match addr {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+    if prefix_len > bytes.len() * 8 {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Prefix length exceeds address width: {cidr}"
+        )));
+    }
+
+    Ok((bytes, prefix_len))
+}
+
+/// 从 CIDR 列表构建 PTV2/PTV3 格式 `.bin` 文件，供 `IpSearcher` 加载。
+///
+/// 先在内存里按字节逐层搭建一棵前缀树，再做一次 BFS 压平：同一层的子节点在
+/// 输出数组里连续排列，这样每个节点只需记录 `BaseOffset`（子节点块起始偏移）
+/// 和 `BaseIndex`（叶子值在 value 表中的起始下标），配合 ChildBitmap/LeafBitmap
+/// 的 popcount 就能还原出 `lookup_code` 里的跳转算法。只要调用过 `add_with_info`，
+/// 输出就会带上字符串池并切换到 PTV3；调用过 `enable_front_table` 则会额外写出
+/// 65536 项的前两字节加速表。两者都不使用时维持原来的纯 PTV2 输出。
+#[pyclass]
+#[derive(Default)]
+struct PoptrieBuilder {
+    root: NodeBuilder,
+    has_strings: bool,
+    front_table: bool,
+}
+
+#[pymethods]
+impl PoptrieBuilder {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加一条 `cidr -> code` 映射，例如 `("10.0.0.0/8", 86)`。
+    fn add(&mut self, cidr: &str, code: u16) -> PyResult<()> {
+        let (bytes, prefix_len) = parse_cidr(cidr)?;
+        self.root.insert(&bytes, prefix_len, code, None);
+        Ok(())
+    }
+
+    /// 批量添加 `(cidr, code)` 二元组。
+    fn add_many(&mut self, entries: Vec<(String, u16)>) -> PyResult<()> {
+        for (cidr, code) in entries {
+            self.add(&cidr, code)?;
+        }
+        Ok(())
+    }
+
+    /// 添加一条带字符串元数据的映射，例如 `("1.2.3.0/24", 0, "CN|Guangdong|Shenzhen|...")`。
+    /// 一旦使用过这个方法，`save`/`build_bytes` 就会输出 PTV3 格式。
+    fn add_with_info(&mut self, cidr: &str, code: u16, info: String) -> PyResult<()> {
+        let (bytes, prefix_len) = parse_cidr(cidr)?;
+        self.root.insert(&bytes, prefix_len, code, Some(info));
+        self.has_strings = true;
+        Ok(())
+    }
+
+    /// 开启 65536 项的前两字节加速表，供 `contains_ip`/`lookup_code` 跳过最前两层
+    /// 的 bitmap/popcount 计算。要求数据集里不存在 /16 及更短的前缀，否则 `save`/
+    /// `build_bytes` 会在构建时报错——这类前缀在消费前两个字节时就要返回结果，
+    /// 没法被加速表的“跳到第三字节”语义表达。
+    fn enable_front_table(&mut self) {
+        self.front_table = true;
+    }
+
+    /// 将当前已添加的所有前缀序列化为字节流（不落盘）。
+    fn build_bytes(&self) -> PyResult<Vec<u8>> {
+        self.serialize()
+    }
+
+    /// 构建并写出 `.bin` 文件，结果可以直接被 `IpSearcher(path)` 加载。
+    fn save(&self, path: String) -> PyResult<()> {
+        std::fs::write(path, self.serialize()?)?;
+        Ok(())
+    }
+}
+
+impl PoptrieBuilder {
+    fn serialize(&self) -> PyResult<Vec<u8>> {
+        if self.front_table {
+            self.validate_front_table()?;
+        }
+
+        // BFS 压平：`pending` 既是待处理队列也是最终的节点顺序。
+        let mut pending: Vec<NodeBuilder> = vec![self.root.clone()];
+        let mut child_bitmaps: Vec<[u8; 32]> = Vec::new();
+        let mut leaf_bitmaps: Vec<[u8; 32]> = Vec::new();
+        let mut base_offsets: Vec<u32> = Vec::new();
+        let mut base_indices: Vec<u32> = Vec::new();
+        let mut values: Vec<u16> = Vec::new();
+        let mut infos: Vec<String> = Vec::new();
+
+        let mut i = 0;
+        while i < pending.len() {
+            let node = std::mem::take(&mut pending[i]);
+
+            let mut leaf_bitmap = [0u8; 32];
+            for &b in node.leaves.keys() {
+                set_bitmap_bit(&mut leaf_bitmap, b);
+            }
+            let base_index = values.len() as u32;
+            for (code, info) in node.leaves.into_values() {
+                values.push(code);
+                infos.push(info.unwrap_or_default());
+            }
+
+            let mut child_bitmap = [0u8; 32];
+            for &b in node.children.keys() {
+                set_bitmap_bit(&mut child_bitmap, b);
+            }
+            // 子节点即将被追加到队尾，此刻的长度就是它们的落点。
+            let base_offset =
+                (IpSearcher::HEADER_SIZE + pending.len() * IpSearcher::NODE_SIZE) as u32;
+            for (_, child) in node.children {
+                pending.push(child);
+            }
+
+            child_bitmaps.push(child_bitmap);
+            leaf_bitmaps.push(leaf_bitmap);
+            base_offsets.push(base_offset);
+            base_indices.push(base_index);
+            i += 1;
+        }
+
+        let node_count = child_bitmaps.len();
+        let mut out = Vec::with_capacity(
+            IpSearcher::HEADER_SIZE + node_count * IpSearcher::NODE_SIZE + values.len() * 2,
+        );
+        out.extend_from_slice(if self.has_strings {
+            IpSearcher::MAGIC_V3
+        } else {
+            IpSearcher::MAGIC
+        });
+        out.extend_from_slice(&(node_count as u32).to_le_bytes());
+        out.extend_from_slice(&(values.len() as u32).to_le_bytes());
+        let flags = if self.front_table {
+            IpSearcher::FLAG_FRONT_TABLE
+        } else {
+            0
+        };
+        out.extend_from_slice(&[flags, 0, 0, 0]);
+
+        for idx in 0..node_count {
+            out.extend_from_slice(&child_bitmaps[idx]);
+            out.extend_from_slice(&leaf_bitmaps[idx]);
+            out.extend_from_slice(&base_offsets[idx].to_le_bytes());
+            out.extend_from_slice(&base_indices[idx].to_le_bytes());
+        }
+        for &v in &values {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+
+        if self.front_table {
+            for entry in Self::build_front_table(&child_bitmaps, &base_offsets) {
+                out.extend_from_slice(&entry.to_le_bytes());
+            }
+        }
+
+        if self.has_strings {
+            let mut blob: Vec<u8> = Vec::new();
+            let mut table: Vec<u8> = Vec::with_capacity(infos.len() * 8);
+            for s in &infos {
+                let bytes = s.as_bytes();
+                table.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+                table.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                blob.extend_from_slice(bytes);
+            }
+            out.extend_from_slice(&table);
+            out.extend_from_slice(&blob);
+        }
+
+        Ok(out)
+    }
+
+    /// 确保数据集里不存在 /16 及更短的前缀：root 本身（/0-/8）和 root 的直接子节点
+    /// （/9-/16）都不能带叶子，否则加速表的“跳到第三字节”语义会丢掉这些前缀。
+    fn validate_front_table(&self) -> PyResult<()> {
+        if !self.root.leaves.is_empty() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Cannot build front table: a prefix shorter than /9 exists (e.g. a /8).",
+            ));
+        }
+        for child in self.root.children.values() {
+            if !child.leaves.is_empty() {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                    "Cannot build front table: a /9-/16 prefix exists.",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// 对每个 (byte0, byte1) 组合，模拟一次正常的逐字节下探，记录消费完两个字节
+    /// 后应处于的节点绝对偏移；如果这条路径在两个字节内就终止，则记为
+    /// `u32::MAX`（"此 /16 下无匹配"）。
+    fn build_front_table(child_bitmaps: &[[u8; 32]], base_offsets: &[u32]) -> Vec<u32> {
+        let mut table = vec![u32::MAX; IpSearcher::FRONT_TABLE_ENTRIES];
+
+        for b0 in 0..=255u8 {
+            if !bitmap_has(&child_bitmaps[0], b0) {
+                continue;
+            }
+            let cursor0 = base_offsets[0] as usize
+                + popcount_before(&child_bitmaps[0], b0) * IpSearcher::NODE_SIZE;
+            let node_index0 = (cursor0 - IpSearcher::HEADER_SIZE) / IpSearcher::NODE_SIZE;
+
+            for b1 in 0..=255u8 {
+                if !bitmap_has(&child_bitmaps[node_index0], b1) {
+                    continue;
+                }
+                let cursor1 = base_offsets[node_index0] as usize
+                    + popcount_before(&child_bitmaps[node_index0], b1) * IpSearcher::NODE_SIZE;
+                table[(b0 as usize) * 256 + b1 as usize] = cursor1 as u32;
+            }
+        }
+
+        table
+    }
+}
+
 #[pymodule]
 fn poptrie(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<IpSearcher>()?;
+    m.add_class::<PoptrieBuilder>()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_bin_path(name: &str) -> std::path::PathBuf {
+        static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let id = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::env::temp_dir().join(format!(
+            "poptrie_test_{}_{}_{}.bin",
+            std::process::id(),
+            name,
+            id
+        ))
+    }
+
+    fn write_temp_bin(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = temp_bin_path(name);
+        std::fs::write(&path, bytes).expect("write temp bin file");
+        path
+    }
+
+    fn load(path: &std::path::Path) -> IpSearcher {
+        IpSearcher::new(path.to_str().unwrap().to_string()).expect("load searcher")
+    }
+
+    #[test]
+    fn round_trip_byte_aligned_prefix() {
+        let mut builder = PoptrieBuilder::new();
+        builder.add("10.0.0.0/8", 42).unwrap();
+        let path = write_temp_bin("byte_aligned", &builder.build_bytes().unwrap());
+        let searcher = load(&path);
+
+        assert!(searcher.contains_ip(&[10, 1, 2, 3]));
+        assert_eq!(searcher.lookup_code(&[10, 1, 2, 3]), 42);
+        assert!(!searcher.contains_ip(&[11, 0, 0, 1]));
+        assert_eq!(searcher.lookup_code(&[11, 0, 0, 1]), 0);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn round_trip_non_byte_aligned_prefix() {
+        // 172.16.0.0/20 只固定了第三字节的高 4 位，覆盖 172.16.0.0-172.16.15.255。
+        let mut builder = PoptrieBuilder::new();
+        builder.add("172.16.0.0/20", 7).unwrap();
+        let path = write_temp_bin("non_byte_aligned", &builder.build_bytes().unwrap());
+        let searcher = load(&path);
+
+        assert!(searcher.contains_ip(&[172, 16, 0, 0]));
+        assert!(searcher.contains_ip(&[172, 16, 15, 255]));
+        assert_eq!(searcher.lookup_code(&[172, 16, 8, 1]), 7);
+        assert!(!searcher.contains_ip(&[172, 16, 16, 0]));
+        assert_eq!(searcher.lookup_code(&[172, 16, 16, 0]), 0);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn round_trip_default_route() {
+        let mut builder = PoptrieBuilder::new();
+        builder.add("0.0.0.0/0", 1).unwrap();
+        let path = write_temp_bin("default_route", &builder.build_bytes().unwrap());
+        let searcher = load(&path);
+
+        for probe in [[0, 0, 0, 0], [255, 255, 255, 255], [8, 8, 8, 8]] {
+            assert!(searcher.contains_ip(&probe));
+            assert_eq!(searcher.lookup_code(&probe), 1);
+        }
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn round_trip_overlapping_prefixes_lpm() {
+        // 一条宽泛的 /8 和嵌套在其中的 /24：非 LPM 查询沿路径命中第一个 LeafBitmap
+        // 位就返回，而 /8 的位在根节点、比嵌套的 /24 更靠前，所以非 LPM 查询只能
+        // 拿到 /8 的值；LPM 版本会继续往下走，在 /24 覆盖到的地址上找回 /24 的值。
+        let mut builder = PoptrieBuilder::new();
+        builder.add("10.0.0.0/8", 8).unwrap();
+        builder.add("10.1.2.0/24", 24).unwrap();
+        let path = write_temp_bin("overlapping", &builder.build_bytes().unwrap());
+        let searcher = load(&path);
+
+        assert_eq!(searcher.lookup_code(&[10, 1, 2, 5]), 8);
+        assert_eq!(searcher.lookup_code_lpm(&[10, 1, 2, 5]), 24);
+        assert_eq!(searcher.lookup_code_lpm(&[10, 9, 9, 9]), 8);
+        assert!(searcher.contains_ip_lpm(&[10, 9, 9, 9]));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn front_table_matches_unaccelerated_for_all_lookup_variants() {
+        fn build(with_front_table: bool) -> PoptrieBuilder {
+            let mut builder = PoptrieBuilder::new();
+            builder.add("10.0.0.0/24", 1).unwrap();
+            builder
+                .add_with_info("10.1.0.0/20", 2, "a|b".to_string())
+                .unwrap();
+            builder.add("192.168.0.0/24", 3).unwrap();
+            if with_front_table {
+                builder.enable_front_table();
+            }
+            builder
+        }
+
+        let plain_path = write_temp_bin("front_table_plain", &build(false).build_bytes().unwrap());
+        let accel_path = write_temp_bin("front_table_accel", &build(true).build_bytes().unwrap());
+
+        let plain = load(&plain_path);
+        let accel = load(&accel_path);
+
+        let probes: [[u8; 4]; 6] = [
+            [10, 0, 0, 1],
+            [10, 1, 5, 200],
+            [10, 1, 16, 0],
+            [192, 168, 0, 5],
+            [1, 2, 3, 4],
+            [255, 255, 255, 255],
+        ];
+
+        for probe in probes {
+            assert_eq!(plain.contains_ip(&probe), accel.contains_ip(&probe));
+            assert_eq!(plain.contains_ip_lpm(&probe), accel.contains_ip_lpm(&probe));
+            assert_eq!(plain.lookup_code(&probe), accel.lookup_code(&probe));
+            assert_eq!(plain.lookup_code_lpm(&probe), accel.lookup_code_lpm(&probe));
+            assert_eq!(plain.lookup_info(&probe), accel.lookup_info(&probe));
+        }
+
+        std::fs::remove_file(&plain_path).ok();
+        std::fs::remove_file(&accel_path).ok();
+    }
+
+    #[test]
+    fn reload_is_safe_under_concurrent_lookups() {
+        let mut builder_a = PoptrieBuilder::new();
+        builder_a.add("10.0.0.0/8", 1).unwrap();
+        let path_a = write_temp_bin("reload_a", &builder_a.build_bytes().unwrap());
+
+        let mut builder_b = PoptrieBuilder::new();
+        builder_b.add("10.0.0.0/8", 2).unwrap();
+        let path_b = write_temp_bin("reload_b", &builder_b.build_bytes().unwrap());
+
+        let searcher = std::sync::Arc::new(load(&path_a));
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let searcher = std::sync::Arc::clone(&searcher);
+                let stop = std::sync::Arc::clone(&stop);
+                std::thread::spawn(move || {
+                    while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                        let code = searcher.lookup_code(&[10, 1, 2, 3]);
+                        assert!(code == 1 || code == 2);
+                    }
+                })
+            })
+            .collect();
+
+        for i in 0..50 {
+            let path = if i % 2 == 0 { &path_b } else { &path_a };
+            searcher.reload(path.to_str().unwrap().to_string()).unwrap();
+        }
+
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        std::fs::remove_file(&path_a).ok();
+        std::fs::remove_file(&path_b).ok();
+    }
+}